@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use glam::Vec2;
 use netcanv_renderer::paws::{Alignment, Color, LineCap, Point, Rect, Renderer, Vector};
 use netcanv_renderer::{BlendMode, RenderBackend, ScalingFilter};
@@ -5,6 +7,7 @@ use netcanv_renderer::{BlendMode, RenderBackend, ScalingFilter};
 use crate::common::{paws_color_to_wgpu, vector_to_vec2};
 use crate::gpu::Gpu;
 use crate::image::Image;
+use crate::text::GlyphKey;
 use crate::transform::Transform;
 use crate::WgpuBackend;
 
@@ -35,6 +38,7 @@ pub(crate) enum Pass {
    RoundedRects,
    Lines,
    Images,
+   Text,
 }
 
 pub(crate) struct FlushContext<'flush> {
@@ -42,12 +46,33 @@ pub(crate) struct FlushContext<'flush> {
    pub model_transform_bind_group: &'flush wgpu::BindGroup,
 }
 
+/// Returns the intersection of two rects, clamping a non-overlapping result to zero size rather
+/// than going negative.
+fn intersect_rects(a: Rect, b: Rect) -> Rect {
+   let left = a.left().max(b.left());
+   let top = a.top().max(b.top());
+   let right = a.right().min(b.right());
+   let bottom = a.bottom().min(b.bottom());
+   Rect::new(Point::new(left, top), Vector::new((right - left).max(0.0), (bottom - top).max(0.0)))
+}
+
 impl WgpuBackend {
    pub(crate) fn rewind(&mut self) {
       self.last_pass = None;
       self.rounded_rects.rewind();
       self.lines.rewind();
       self.images.rewind();
+      self.text.rewind();
+   }
+
+   /// Returns the clip rect currently in effect, in device pixels. A `push` scope inherits the
+   /// enclosing scope's clip; the whole render target is returned only if no clip is in effect at
+   /// all.
+   fn current_clip_rect(&self) -> Rect {
+      self.clip_stack.last().copied().unwrap_or_else(|| {
+         let (width, height) = self.gpu.size();
+         Rect::new(Point::new(0.0, 0.0), Vector::new(width as f32, height as f32))
+      })
    }
 
    fn switch_pass(&mut self, new_pass: Pass) {
@@ -88,6 +113,14 @@ impl WgpuBackend {
             depth_stencil_attachment: None,
          });
 
+         let clip_rect = self.current_clip_rect();
+         render_pass.set_scissor_rect(
+            clip_rect.left().max(0.0) as u32,
+            clip_rect.top().max(0.0) as u32,
+            (clip_rect.right() - clip_rect.left()).max(0.0) as u32,
+            (clip_rect.bottom() - clip_rect.top()).max(0.0) as u32,
+         );
+
          let mut context = FlushContext {
             gpu: &self.gpu,
             model_transform_bind_group,
@@ -96,6 +129,7 @@ impl WgpuBackend {
          self.rounded_rects.flush(&mut context, &mut render_pass);
          self.lines.flush(&mut context, &mut render_pass);
          self.images.flush(&mut context, &self.image_storage, &mut render_pass);
+         self.text.flush(&mut context, &self.glyph_atlas, &mut render_pass);
          self.last_pass = None;
       }
 
@@ -122,6 +156,8 @@ impl Renderer for WgpuBackend {
    fn push(&mut self) {
       let transform = *self.current_transform();
       self.transform_stack.push(transform);
+      let clip_rect = self.current_clip_rect();
+      self.clip_stack.push(clip_rect);
    }
 
    fn pop(&mut self) {
@@ -132,6 +168,12 @@ impl Renderer for WgpuBackend {
       if self.transform_stack.is_empty() {
          self.transform_stack.push(Transform::Translation(Vec2::ZERO));
       }
+      if !self.clip_stack.is_empty() {
+         // The clip rect is applied to the whole render pass in `flush`, not per primitive, so
+         // anything queued under the clip being popped has to be drawn before it's gone.
+         self.flush();
+         self.clip_stack.pop();
+      }
    }
 
    fn translate(&mut self, vec: Vector) {
@@ -142,7 +184,17 @@ impl Renderer for WgpuBackend {
       }
    }
 
-   fn clip(&mut self, rect: Rect) {}
+   fn clip(&mut self, rect: Rect) {
+      // Like matrix transforms, the clip rect is only realized once per `flush`, so switching it
+      // has to flush whatever was queued under the old one first.
+      self.flush();
+      let rect = self.current_transform().translate_rect(rect);
+      let clipped = intersect_rects(self.current_clip_rect(), rect);
+      match self.clip_stack.last_mut() {
+         Some(top) => *top = clipped,
+         None => self.clip_stack.push(clipped),
+      }
+   }
 
    fn fill(&mut self, rect: Rect, color: Color, radius: f32) {
       if color.a > 0 {
@@ -186,7 +238,56 @@ impl Renderer for WgpuBackend {
       color: Color,
       alignment: Alignment,
    ) -> f32 {
-      32.0
+      let total_width = font.text_width(text);
+      if color.a == 0 || text.is_empty() {
+         return total_width;
+      }
+
+      let line_height = font.height();
+      let left = rect.left();
+      let h_center = rect.left() + (rect.width() - total_width) / 2.0;
+      let right = rect.right() - total_width;
+      let top = rect.top();
+      let v_center = rect.top() + (rect.height() - line_height) / 2.0;
+      let bottom = rect.bottom() - line_height;
+      let (mut pen_x, y) = match alignment {
+         Alignment::TopLeft => (left, top),
+         Alignment::Top => (h_center, top),
+         Alignment::TopRight => (right, top),
+         Alignment::Left => (left, v_center),
+         Alignment::Center => (h_center, v_center),
+         Alignment::Right => (right, v_center),
+         Alignment::BottomLeft => (left, bottom),
+         Alignment::Bottom => (h_center, bottom),
+         Alignment::BottomRight => (right, bottom),
+      };
+
+      let transform = *self.current_transform();
+      self.switch_pass(Pass::Text);
+      for glyph in text.chars() {
+         let (metrics, bitmap) = font.rasterize(glyph);
+         if metrics.width > 0 && metrics.height > 0 {
+            let uv_rect = self.glyph_atlas.get_or_insert(
+               &self.gpu,
+               font.glyph_key(glyph),
+               metrics.width as u32,
+               metrics.height as u32,
+               &bitmap,
+            );
+            let glyph_rect = Rect::new(
+               Point::new(pen_x + metrics.xmin as f32, y + line_height - metrics.height as f32 - metrics.ymin as f32),
+               Vector::new(metrics.width as f32, metrics.height as f32),
+            );
+            let glyph_rect = transform.translate_rect(glyph_rect);
+            self.text.add(glyph_rect, uv_rect, color);
+            if self.text.needs_flush() {
+               self.flush();
+            }
+         }
+         pen_x += metrics.advance_width;
+      }
+
+      total_width
    }
 }
 
@@ -200,14 +301,23 @@ impl RenderBackend for WgpuBackend {
    }
 
    fn create_font_from_memory(&mut self, data: &[u8], default_size: f32) -> Self::Font {
-      Font
+      Font::from_memory(data, default_size)
    }
 
    fn create_framebuffer(&mut self, width: u32, height: u32) -> Self::Framebuffer {
-      Framebuffer
+      Framebuffer::new(self.gpu.clone(), width, height)
    }
 
-   fn draw_to(&mut self, framebuffer: &Self::Framebuffer, f: impl FnOnce(&mut Self)) {}
+   fn draw_to(&mut self, framebuffer: &Self::Framebuffer, f: impl FnOnce(&mut Self)) {
+      // Finish whatever was queued for the previous target before redirecting passes into the
+      // framebuffer, and flush again afterwards so `f`'s draws don't leak onto whatever is
+      // rendered next.
+      self.flush();
+      let previous_target = self.gpu.push_render_target(framebuffer.view().clone());
+      f(self);
+      self.flush();
+      self.gpu.pop_render_target(previous_target);
+   }
 
    fn clear(&mut self, color: Color) {
       self.clear = Some(color);
@@ -224,7 +334,14 @@ impl RenderBackend for WgpuBackend {
       }
    }
 
-   fn framebuffer(&mut self, rect: Rect, framebuffer: &Self::Framebuffer) {}
+   fn framebuffer(&mut self, rect: Rect, framebuffer: &Self::Framebuffer) {
+      let rect = self.current_transform().translate_rect(rect);
+      self.switch_pass(Pass::Images);
+      self.images.add_view(rect, framebuffer.view(), framebuffer.scaling_filter);
+      if self.images.needs_flush() {
+         self.flush();
+      }
+   }
 
    fn scale(&mut self, scale: Vector) {
       // In case of scaling we always end up with a matrix so we need to flush whatever was about
@@ -237,36 +354,269 @@ impl RenderBackend for WgpuBackend {
    fn set_blend_mode(&mut self, new_blend_mode: BlendMode) {}
 }
 
-pub struct Framebuffer;
+/// A render target backed by a `wgpu::Texture`, so it can be drawn into with the same render
+/// passes as the main surface, and its pixels can be read back for layers, the infinite canvas,
+/// and PNG export.
+pub struct Framebuffer {
+   gpu: Gpu,
+   width: u32,
+   height: u32,
+   texture: wgpu::Texture,
+   view: wgpu::TextureView,
+   scaling_filter: ScalingFilter,
+   /// Matches `gpu.surface_format()` rather than being hardcoded, so a framebuffer can be drawn
+   /// into by the same render passes (and pipelines, which are built against the surface format)
+   /// used for the main surface.
+   format: wgpu::TextureFormat,
+}
+
+impl Framebuffer {
+   const BYTES_PER_PIXEL: u32 = 4;
+
+   /// Whether `self.format`'s channel order is reversed from the R-G-B-A order the
+   /// `upload_rgba`/`download_rgba` trait contract promises, and so needs a per-pixel R/B swap on
+   /// the way in and out.
+   fn swizzles_red_and_blue(&self) -> bool {
+      matches!(
+         self.format,
+         wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+      )
+   }
+
+   pub(crate) fn new(gpu: Gpu, width: u32, height: u32) -> Self {
+      let format = gpu.surface_format();
+      let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+         label: Some("Framebuffer"),
+         size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+         },
+         mip_level_count: 1,
+         sample_count: 1,
+         dimension: wgpu::TextureDimension::D2,
+         format,
+         usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::TEXTURE_BINDING,
+         view_formats: &[],
+      });
+      let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+      Self {
+         gpu,
+         width,
+         height,
+         texture,
+         view,
+         scaling_filter: ScalingFilter::Linear,
+         format,
+      }
+   }
+
+   pub(crate) fn view(&self) -> &wgpu::TextureView {
+      &self.view
+   }
+
+   /// Rounds `bytes_per_row` up to the alignment wgpu requires for buffer-texture copies.
+   fn padded_bytes_per_row(width: u32) -> u32 {
+      let unpadded = width * Self::BYTES_PER_PIXEL;
+      let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+      (unpadded + align - 1) / align * align
+   }
+}
 
 impl netcanv_renderer::Framebuffer for Framebuffer {
    fn size(&self) -> (u32, u32) {
-      (256, 256)
+      (self.width, self.height)
    }
 
-   fn upload_rgba(&mut self, position: (u32, u32), size: (u32, u32), pixels: &[u8]) {}
+   fn upload_rgba(&mut self, position: (u32, u32), size: (u32, u32), pixels: &[u8]) {
+      let (x, y) = position;
+      let (width, height) = size;
 
-   fn download_rgba(&self, position: (u32, u32), size: (u32, u32), dest: &mut [u8]) {}
+      // `pixels` is always in R-G-B-A order per the trait contract, but the texture may be
+      // backed by a B-G-R-A surface format, so swap channels into a scratch buffer before upload.
+      let swizzled;
+      let pixels = if self.swizzles_red_and_blue() {
+         swizzled = swizzle_red_and_blue(pixels);
+         &swizzled
+      } else {
+         pixels
+      };
 
-   fn set_scaling_filter(&mut self, filter: ScalingFilter) {}
+      self.gpu.queue.write_texture(
+         wgpu::ImageCopyTexture {
+            texture: &self.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+         },
+         pixels,
+         wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width * Self::BYTES_PER_PIXEL),
+            rows_per_image: Some(height),
+         },
+         wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+         },
+      );
+   }
+
+   fn download_rgba(&self, position: (u32, u32), size: (u32, u32), dest: &mut [u8]) {
+      let (x, y) = position;
+      let (width, height) = size;
+      let unpadded_bytes_per_row = width * Self::BYTES_PER_PIXEL;
+      let padded_bytes_per_row = Self::padded_bytes_per_row(width);
+
+      let download_buffer = self.gpu.device.create_buffer(&wgpu::BufferDescriptor {
+         label: Some("Framebuffer download"),
+         size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+         usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+         mapped_at_creation: false,
+      });
+
+      let mut encoder = self.gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+         label: Some("Framebuffer download"),
+      });
+      encoder.copy_texture_to_buffer(
+         wgpu::ImageCopyTexture {
+            texture: &self.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+         },
+         wgpu::ImageCopyBuffer {
+            buffer: &download_buffer,
+            layout: wgpu::ImageDataLayout {
+               offset: 0,
+               bytes_per_row: Some(padded_bytes_per_row),
+               rows_per_image: Some(height),
+            },
+         },
+         wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+         },
+      );
+      self.gpu.queue.submit(Some(encoder.finish()));
+
+      let slice = download_buffer.slice(..);
+      let (tx, rx) = std::sync::mpsc::channel();
+      slice.map_async(wgpu::MapMode::Read, move |result| {
+         let _ = tx.send(result);
+      });
+      self.gpu.device.poll(wgpu::Maintain::Wait);
+      rx.recv()
+         .expect("map_async callback was dropped without being called")
+         .expect("failed to map framebuffer download buffer");
+
+      {
+         let mapped = slice.get_mapped_range();
+         for row in 0..height as usize {
+            let src_start = row * padded_bytes_per_row as usize;
+            let dst_start = row * unpadded_bytes_per_row as usize;
+            dest[dst_start..dst_start + unpadded_bytes_per_row as usize]
+               .copy_from_slice(&mapped[src_start..src_start + unpadded_bytes_per_row as usize]);
+         }
+      }
+      download_buffer.unmap();
+
+      // The texture is in B-G-R-A order when backed by a BGRA surface, but `dest` must come back
+      // as R-G-B-A per the trait contract, so swap channels back in place.
+      if self.swizzles_red_and_blue() {
+         swizzle_red_and_blue_in_place(&mut dest[..unpadded_bytes_per_row as usize * height as usize]);
+      }
+   }
+
+   fn set_scaling_filter(&mut self, filter: ScalingFilter) {
+      self.scaling_filter = filter;
+   }
 }
 
-pub struct Font;
+/// Swaps the R and B channels of every pixel into a freshly allocated buffer.
+fn swizzle_red_and_blue(pixels: &[u8]) -> Vec<u8> {
+   let mut swizzled = pixels.to_vec();
+   swizzle_red_and_blue_in_place(&mut swizzled);
+   swizzled
+}
+
+/// Swaps the R and B channels of every pixel in place. `pixels` must be a whole number of 4-byte
+/// RGBA/BGRA pixels.
+fn swizzle_red_and_blue_in_place(pixels: &mut [u8]) {
+   for pixel in pixels.chunks_exact_mut(4) {
+      pixel.swap(0, 2);
+   }
+}
+
+struct FontData {
+   id: u64,
+   fontdue: fontdue::Font,
+}
+
+/// A loaded font at a given size. Cloning a `Font` (e.g. via `with_size`) is cheap: the
+/// underlying `fontdue::Font` is shared, and only the size changes.
+#[derive(Clone)]
+pub struct Font {
+   data: Arc<FontData>,
+   size: f32,
+}
+
+impl Font {
+   fn from_memory(data: &[u8], default_size: f32) -> Self {
+      use std::sync::atomic::{AtomicU64, Ordering};
+      static NEXT_FONT_ID: AtomicU64 = AtomicU64::new(0);
+
+      let fontdue = fontdue::Font::from_bytes(data, fontdue::FontSettings::default())
+         .expect("invalid font data passed to create_font_from_memory");
+      Self {
+         data: Arc::new(FontData {
+            id: NEXT_FONT_ID.fetch_add(1, Ordering::Relaxed),
+            fontdue,
+         }),
+         size: default_size,
+      }
+   }
+
+   fn glyph_key(&self, glyph: char) -> GlyphKey {
+      GlyphKey {
+         font_id: self.data.id,
+         glyph_id: self.data.fontdue.lookup_glyph_index(glyph),
+         quantized_size: GlyphKey::quantize_size(self.size),
+      }
+   }
+
+   fn rasterize(&self, glyph: char) -> (fontdue::Metrics, Vec<u8>) {
+      self.data.fontdue.rasterize(glyph, self.size)
+   }
+}
 
 impl netcanv_renderer::Font for Font {
    fn with_size(&self, new_size: f32) -> Self {
-      Font
+      Self {
+         data: Arc::clone(&self.data),
+         size: new_size,
+      }
    }
 
    fn size(&self) -> f32 {
-      14.0
+      self.size
    }
 
    fn height(&self) -> f32 {
-      14.0
+      self
+         .data
+         .fontdue
+         .horizontal_line_metrics(self.size)
+         .expect("font has no horizontal metrics")
+         .new_line_size
    }
 
    fn text_width(&self, text: &str) -> f32 {
-      32.0
+      text.chars().map(|glyph| self.data.fontdue.metrics(glyph, self.size).advance_width).sum()
    }
 }
@@ -0,0 +1,378 @@
+//! Glyph-atlas text rendering: glyphs are rasterized on the CPU with `fontdue`, cached in a
+//! dynamically grown GPU atlas texture, and drawn as one textured quad per glyph in the `Text`
+//! pass.
+
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use netcanv_renderer::paws::{Color, Point, Rect, Vector};
+
+use crate::common::paws_color_to_wgpu;
+use crate::gpu::Gpu;
+use crate::rendering::FlushContext;
+
+/// Identifies a cached glyph rasterization: the font it came from, which glyph, and the pixel
+/// size it was rasterized at, quantized so that nearby sizes share a cache entry instead of
+/// filling the atlas with near-duplicate bitmaps.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct GlyphKey {
+   pub font_id: u64,
+   pub glyph_id: u16,
+   pub quantized_size: u32,
+}
+
+impl GlyphKey {
+   pub fn quantize_size(size: f32) -> u32 {
+      (size * 4.0).round() as u32
+   }
+}
+
+struct AtlasSlot {
+   uv_rect: Rect,
+}
+
+/// A dynamically grown atlas texture holding rasterized glyph bitmaps, shared by every font the
+/// backend has created. New glyphs are packed into shelves left-to-right, growing a new shelf
+/// downward when the current one runs out of room. When it runs out of vertical space, the whole
+/// texture is reallocated at double the size and starts out empty; cached glyphs are not carried
+/// over.
+pub(crate) struct GlyphAtlas {
+   texture: wgpu::Texture,
+   view: wgpu::TextureView,
+   bind_group_layout: wgpu::BindGroupLayout,
+   bind_group: wgpu::BindGroup,
+   sampler: wgpu::Sampler,
+   size: u32,
+   cursor: (u32, u32),
+   shelf_height: u32,
+   slots: HashMap<GlyphKey, AtlasSlot>,
+}
+
+impl GlyphAtlas {
+   const INITIAL_SIZE: u32 = 512;
+   const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+   pub fn new(gpu: &Gpu) -> Self {
+      Self::with_size(gpu, Self::INITIAL_SIZE)
+   }
+
+   fn with_size(gpu: &Gpu, size: u32) -> Self {
+      let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+         label: Some("Glyph atlas"),
+         size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+         },
+         mip_level_count: 1,
+         sample_count: 1,
+         dimension: wgpu::TextureDimension::D2,
+         format: Self::FORMAT,
+         usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+         view_formats: &[],
+      });
+      let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+      let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+         label: Some("Glyph atlas sampler"),
+         mag_filter: wgpu::FilterMode::Linear,
+         min_filter: wgpu::FilterMode::Linear,
+         ..Default::default()
+      });
+      let bind_group_layout =
+         gpu.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Glyph atlas bind group layout"),
+            entries: &[
+               wgpu::BindGroupLayoutEntry {
+                  binding: 0,
+                  visibility: wgpu::ShaderStages::FRAGMENT,
+                  ty: wgpu::BindingType::Texture {
+                     sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                     view_dimension: wgpu::TextureViewDimension::D2,
+                     multisampled: false,
+                  },
+                  count: None,
+               },
+               wgpu::BindGroupLayoutEntry {
+                  binding: 1,
+                  visibility: wgpu::ShaderStages::FRAGMENT,
+                  ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                  count: None,
+               },
+            ],
+         });
+      let bind_group = Self::make_bind_group(gpu, &bind_group_layout, &view, &sampler);
+
+      Self {
+         texture,
+         view,
+         bind_group_layout,
+         bind_group,
+         sampler,
+         size,
+         cursor: (0, 0),
+         shelf_height: 0,
+         slots: HashMap::new(),
+      }
+   }
+
+   fn make_bind_group(
+      gpu: &Gpu,
+      layout: &wgpu::BindGroupLayout,
+      view: &wgpu::TextureView,
+      sampler: &wgpu::Sampler,
+   ) -> wgpu::BindGroup {
+      gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+         label: Some("Glyph atlas bind group"),
+         layout,
+         entries: &[
+            wgpu::BindGroupEntry {
+               binding: 0,
+               resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+               binding: 1,
+               resource: wgpu::BindingResource::Sampler(sampler),
+            },
+         ],
+      })
+   }
+
+   /// Returns the UV rectangle (in `0..1` atlas space) for the given glyph, uploading the
+   /// caller-rasterized `bitmap` into the atlas first if it isn't cached yet.
+   pub fn get_or_insert(
+      &mut self,
+      gpu: &Gpu,
+      key: GlyphKey,
+      width: u32,
+      height: u32,
+      bitmap: &[u8],
+   ) -> Rect {
+      if let Some(slot) = self.slots.get(&key) {
+         return slot.uv_rect;
+      }
+
+      if self.cursor.0 + width > self.size {
+         self.cursor = (0, self.cursor.1 + self.shelf_height);
+         self.shelf_height = 0;
+      }
+      if self.cursor.1 + height > self.size {
+         self.grow(gpu);
+         return self.get_or_insert(gpu, key, width, height, bitmap);
+      }
+
+      let (x, y) = self.cursor;
+      gpu.queue.write_texture(
+         wgpu::ImageCopyTexture {
+            texture: &self.texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+         },
+         bitmap,
+         wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width),
+            rows_per_image: Some(height),
+         },
+         wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+         },
+      );
+
+      self.cursor.0 += width;
+      self.shelf_height = self.shelf_height.max(height);
+
+      let scale = self.size as f32;
+      let uv_rect = Rect::new(
+         Point::new(x as f32 / scale, y as f32 / scale),
+         Vector::new(width as f32 / scale, height as f32 / scale),
+      );
+      self.slots.insert(key, AtlasSlot { uv_rect });
+      uv_rect
+   }
+
+   /// Doubles the atlas size, replacing it with an empty one: every glyph cached so far is
+   /// dropped and gets uploaded again on its next `get_or_insert`. Rare in practice: most sessions
+   /// only ever touch a handful of sizes of a handful of fonts.
+   fn grow(&mut self, gpu: &Gpu) {
+      log::info!("glyph atlas full at {0}x{0}, growing to {1}x{1}", self.size, self.size * 2);
+      *self = Self::with_size(gpu, self.size * 2);
+   }
+
+   pub fn bind_group(&self) -> &wgpu::BindGroup {
+      &self.bind_group
+   }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GlyphVertex {
+   position: [f32; 2],
+   uv: [f32; 2],
+   color: [f32; 4],
+}
+
+const MAX_GLYPHS_PER_BATCH: usize = 4096;
+
+/// Batches glyph quads queued by `Renderer::text` until the next `flush`.
+pub(crate) struct Text {
+   vertices: Vec<GlyphVertex>,
+   vertex_buffer: wgpu::Buffer,
+   pipeline: wgpu::RenderPipeline,
+   viewport_buffer: wgpu::Buffer,
+   viewport_bind_group: wgpu::BindGroup,
+}
+
+impl Text {
+   /// `model_transform_bind_group_layout` must match the layout of the bind group every other
+   /// pass binds from `FlushContext.model_transform_bind_group`, so glyphs go through the same
+   /// pan/zoom transform as fills, lines and images.
+   pub fn new(
+      gpu: &Gpu,
+      model_transform_bind_group_layout: &wgpu::BindGroupLayout,
+      atlas_bind_group_layout: &wgpu::BindGroupLayout,
+   ) -> Self {
+      let viewport_bind_group_layout =
+         gpu.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text viewport bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+               binding: 0,
+               visibility: wgpu::ShaderStages::VERTEX,
+               ty: wgpu::BindingType::Buffer {
+                  ty: wgpu::BufferBindingType::Uniform,
+                  has_dynamic_offset: false,
+                  min_binding_size: None,
+               },
+               count: None,
+            }],
+         });
+      let viewport_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+         label: Some("Text viewport buffer"),
+         size: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+         mapped_at_creation: false,
+      });
+      let viewport_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+         label: Some("Text viewport bind group"),
+         layout: &viewport_bind_group_layout,
+         entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: viewport_buffer.as_entire_binding(),
+         }],
+      });
+
+      let shader = gpu.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+         label: Some("Text shader"),
+         source: wgpu::ShaderSource::Wgsl(include_str!("text.wgsl").into()),
+      });
+      let pipeline_layout =
+         gpu.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text pipeline layout"),
+            bind_group_layouts: &[
+               model_transform_bind_group_layout,
+               atlas_bind_group_layout,
+               &viewport_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+         });
+      let pipeline = gpu.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+         label: Some("Text pipeline"),
+         layout: Some(&pipeline_layout),
+         vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+               array_stride: std::mem::size_of::<GlyphVertex>() as wgpu::BufferAddress,
+               step_mode: wgpu::VertexStepMode::Vertex,
+               attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4],
+            }],
+         },
+         fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+               format: gpu.surface_format(),
+               blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+               write_mask: wgpu::ColorWrites::ALL,
+            })],
+         }),
+         primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+         },
+         depth_stencil: None,
+         multisample: wgpu::MultisampleState::default(),
+         multiview: None,
+      });
+      let vertex_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+         label: Some("Text vertex buffer"),
+         size: (MAX_GLYPHS_PER_BATCH * 6 * std::mem::size_of::<GlyphVertex>())
+            as wgpu::BufferAddress,
+         usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+         mapped_at_creation: false,
+      });
+      Self {
+         vertices: Vec::new(),
+         vertex_buffer,
+         pipeline,
+         viewport_buffer,
+         viewport_bind_group,
+      }
+   }
+
+   pub fn rewind(&mut self) {
+      self.vertices.clear();
+   }
+
+   pub fn needs_flush(&self) -> bool {
+      self.vertices.len() + 6 > MAX_GLYPHS_PER_BATCH * 6
+   }
+
+   /// Queues one glyph quad: `rect` in device space, `uv_rect` into the glyph atlas.
+   pub fn add(&mut self, rect: Rect, uv_rect: Rect, color: Color) {
+      let color = paws_color_to_wgpu(color);
+      let color = [color.r as f32, color.g as f32, color.b as f32, color.a as f32];
+      let corner = |dx: f32, dy: f32| GlyphVertex {
+         position: [rect.left() + rect.width() * dx, rect.top() + rect.height() * dy],
+         uv: [uv_rect.left() + uv_rect.width() * dx, uv_rect.top() + uv_rect.height() * dy],
+         color,
+      };
+      let (top_left, top_right, bottom_left, bottom_right) =
+         (corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0), corner(1.0, 1.0));
+      self.vertices.extend_from_slice(&[
+         top_left,
+         top_right,
+         bottom_right,
+         top_left,
+         bottom_right,
+         bottom_left,
+      ]);
+   }
+
+   pub fn flush(
+      &mut self,
+      context: &mut FlushContext,
+      atlas: &GlyphAtlas,
+      render_pass: &mut wgpu::RenderPass,
+   ) {
+      if self.vertices.is_empty() {
+         return;
+      }
+      let (width, height) = context.gpu.size();
+      context.gpu.queue.write_buffer(
+         &self.viewport_buffer,
+         0,
+         bytemuck::bytes_of(&[width as f32, height as f32]),
+      );
+      context.gpu.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+      render_pass.set_pipeline(&self.pipeline);
+      render_pass.set_bind_group(0, context.model_transform_bind_group, &[]);
+      render_pass.set_bind_group(1, atlas.bind_group(), &[]);
+      render_pass.set_bind_group(2, &self.viewport_bind_group, &[]);
+      render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+      render_pass.draw(0..self.vertices.len() as u32, 0..1);
+      self.rewind();
+   }
+}
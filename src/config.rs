@@ -56,6 +56,61 @@ pub struct UiConfig {
    pub toolbar_position: ToolbarPosition,
 }
 
+/// Screencast (PipeWire) configuration options.
+#[derive(Deserialize, Serialize)]
+pub struct ScreencastConfig {
+   #[serde(default)]
+   pub enabled: bool,
+   #[serde(default = "default_screencast_framerate")]
+   pub framerate: u32,
+}
+
+impl Default for ScreencastConfig {
+   fn default() -> Self {
+      Self {
+         enabled: false,
+         framerate: default_screencast_framerate(),
+      }
+   }
+}
+
+fn default_screencast_framerate() -> u32 {
+   30
+}
+
+/// Per-script permission flags, keyed by script name.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ScriptPermissions {
+   /// Whether the script is allowed to run at all. Scripts are disabled by default when first
+   /// discovered, so installing one doesn't silently grant it canvas access.
+   #[serde(default)]
+   pub enabled: bool,
+}
+
+impl Default for ScriptPermissions {
+   fn default() -> Self {
+      Self { enabled: false }
+   }
+}
+
+/// Scripting (custom brushes and canvas automation) configuration options.
+#[derive(Deserialize, Serialize)]
+pub struct ScriptingConfig {
+   #[serde(default)]
+   pub enabled: bool,
+   #[serde(default)]
+   pub permissions: std::collections::HashMap<String, ScriptPermissions>,
+}
+
+impl Default for ScriptingConfig {
+   fn default() -> Self {
+      Self {
+         enabled: false,
+         permissions: Default::default(),
+      }
+   }
+}
+
 /// Window position and size.
 #[derive(Deserialize, Serialize)]
 pub struct WindowConfig {
@@ -79,6 +134,12 @@ pub struct UserConfig {
 
    #[serde(default)]
    pub keymap: Keymap,
+
+   #[serde(default)]
+   pub screencast: ScreencastConfig,
+
+   #[serde(default)]
+   pub scripting: ScriptingConfig,
 }
 
 impl UserConfig {
@@ -94,6 +155,11 @@ impl UserConfig {
       Self::config_dir().join("config.toml")
    }
 
+   /// Returns the directory user scripts are loaded from, next to `config.toml`.
+   pub fn scripts_dir() -> PathBuf {
+      Self::config_dir().join("scripts")
+   }
+
    /// Loads the `config.toml` file.
    ///
    /// If the `config.toml` doesn't exist, it's created with values inherited from
@@ -147,6 +213,8 @@ impl Default for UserConfig {
          },
          window: None,
          keymap: Default::default(),
+         screencast: Default::default(),
+         scripting: Default::default(),
       }
    }
 }
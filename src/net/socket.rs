@@ -1,11 +1,138 @@
 // socket abstraction.
 
 use std::fmt::Display;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use serde::{de::DeserializeOwned, Serialize};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+use url::Url;
+
+/// Whether the other end of a `Remote` connection understands the zlib-compressed packet
+/// framing, or only speaks the original uncompressed stream. Sent as the very first byte on the
+/// connection, before any bincode packets, so both old and new peers can interoperate.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Capability {
+    Plain = 0,
+    Compressed = 1,
+}
+
+impl Capability {
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => Self::Compressed,
+            _ => Self::Plain,
+        }
+    }
+}
+
+/// Performs the one-byte capability handshake and reports whether compression should be used for
+/// the rest of the connection: only when *both* peers advertise support for it.
+///
+/// An older relay that doesn't know about this handshake will never send its capability byte, so
+/// the read is bounded by a short timeout rather than blocking forever: a timed-out read is
+/// treated the same as an explicit `Capability::Plain`, and the stream falls back to uncompressed
+/// framing.
+fn negotiate_compression(stream: &mut TcpStream) -> anyhow::Result<bool> {
+    stream.write_all(&[Capability::Compressed.to_byte()])?;
+
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let mut peer_capability = [0u8; 1];
+    let compressed = match stream.read_exact(&mut peer_capability) {
+        Ok(()) => Capability::from_byte(peer_capability[0]) == Capability::Compressed,
+        Err(error)
+            if error.kind() == std::io::ErrorKind::WouldBlock
+                || error.kind() == std::io::ErrorKind::TimedOut =>
+        {
+            log::warn!("peer did not respond to the compression handshake in time, falling back to uncompressed");
+            false
+        }
+        Err(error) => return Err(error.into()),
+    };
+    stream.set_read_timeout(None)?;
+
+    Ok(compressed)
+}
+
+/// The write half of a `Remote` connection, optionally wrapped in a zlib stream.
+enum PacketWriter {
+    Plain(BufWriter<TcpStream>),
+    Compressed(ZlibEncoder<BufWriter<TcpStream>>),
+}
+
+impl Write for PacketWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Compressed(writer) => writer.write(buf),
+        }
+    }
+
+    /// Flushing a `Compressed` writer performs a Z_SYNC_FLUSH, emitting enough compressed bytes
+    /// for the decoder on the other end to produce the packet immediately, rather than stalling
+    /// until a full deflate block accumulates.
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Compressed(writer) => writer.flush(),
+        }
+    }
+}
+
+/// The read half of a `Remote` connection, optionally unwrapping a zlib stream.
+enum PacketReader {
+    Plain(BufReader<TcpStream>),
+    Compressed(ZlibDecoder<BufReader<TcpStream>>),
+}
+
+impl Read for PacketReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read(buf),
+            Self::Compressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+type WsStream = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Connects a `ws://`/`wss://` URL, setting a short read timeout on the underlying socket so a
+/// blocked `read()` on the recv thread periodically lets go of the shared `Mutex`, giving the
+/// send thread a turn instead of starving it for the lifetime of the connection.
+///
+/// The timeout is set *after* `client_tls` has finished the (potentially slow, network-bound)
+/// WebSocket/TLS handshake, not before: a 50ms timeout installed on the raw `TcpStream` ahead of
+/// time would hit `WouldBlock` mid-handshake on any real network. It still needs to be pulled out
+/// of `MaybeTlsStream` explicitly so it reaches the underlying `TcpStream` for `wss://` too, not
+/// just `ws://`.
+fn connect_websocket(url: &Url) -> anyhow::Result<WsStream> {
+    let host = url.host_str().ok_or_else(|| anyhow::anyhow!("URL has no host"))?;
+    let port = url.port_or_known_default().ok_or_else(|| anyhow::anyhow!("URL has no port"))?;
+    let stream = TcpStream::connect((host, port))?;
+    let (socket, _response) = tungstenite::client_tls(url.as_str(), stream)?;
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(Duration::from_millis(50)))?,
+        MaybeTlsStream::NativeTls(stream) => {
+            stream.get_ref().set_read_timeout(Some(Duration::from_millis(50)))?
+        }
+        MaybeTlsStream::Rustls(stream) => {
+            stream.get_ref().set_read_timeout(Some(Duration::from_millis(50)))?
+        }
+        _ => anyhow::bail!("unsupported TLS stream variant"),
+    }
+    Ok(socket)
+}
 
 struct Finished<T: Display + Send>(Option<T>);
 struct Abort;
@@ -64,16 +191,35 @@ pub struct Remote<P: Serialize + DeserializeOwned + Send + 'static> {
 }
 
 impl<P: Serialize + DeserializeOwned + Send + core::fmt::Debug + 'static> Remote<P> {
-    pub fn new(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
-        let stream = TcpStream::connect(addr)?;
+    /// Connects to a relay. `target` is interpreted by scheme: `ws://`/`wss://` opens a
+    /// WebSocket (with TLS for `wss://`), anything else is treated as a `host:port` pair and
+    /// opened as a raw, optionally zlib-compressed, TCP stream.
+    pub fn new(target: &str, compression: Compression) -> anyhow::Result<Self> {
+        match Url::parse(target) {
+            Ok(url) if url.scheme() == "ws" || url.scheme() == "wss" => Self::new_websocket(&url),
+            _ => Self::new_tcp(target, compression),
+        }
+    }
+
+    /// Connects over raw TCP and negotiates a zlib-compressed packet stream at the given
+    /// `compression` level, falling back to the original uncompressed framing if the peer
+    /// doesn't advertise support for it.
+    fn new_tcp(addr: impl ToSocketAddrs, compression: Compression) -> anyhow::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
         stream.set_nodelay(true)?;
+        let compressed = negotiate_compression(&mut stream)?;
 
         let (to_thread, from_main) = crossbeam_channel::unbounded();
         let (to_main, from_thread) = crossbeam_channel::unbounded();
 
         const MEGABYTE: usize = 1024 * 1024;
 
-        let mut writer = BufWriter::with_capacity(2 * MEGABYTE, stream.try_clone()?);
+        let buffered_writer = BufWriter::with_capacity(2 * MEGABYTE, stream.try_clone()?);
+        let mut writer = if compressed {
+            PacketWriter::Compressed(ZlibEncoder::new(buffered_writer, compression))
+        } else {
+            PacketWriter::Plain(buffered_writer)
+        };
         let send = ControllableThread::new("network send thread", move |abort| -> anyhow::Result<()> {
             loop {
                 if let Ok(_) | Err(TryRecvError::Disconnected) = abort.try_recv() {
@@ -87,7 +233,12 @@ impl<P: Serialize + DeserializeOwned + Send + core::fmt::Debug + 'static> Remote
             Ok(())
         });
 
-        let mut reader = BufReader::with_capacity(2 * MEGABYTE, stream.try_clone()?);
+        let buffered_reader = BufReader::with_capacity(2 * MEGABYTE, stream.try_clone()?);
+        let mut reader = if compressed {
+            PacketReader::Compressed(ZlibDecoder::new(buffered_reader))
+        } else {
+            PacketReader::Plain(buffered_reader)
+        };
         let recv = ControllableThread::new("network recv thread", move |abort| -> anyhow::Result<()> {
             loop {
                 if let Ok(_) | Err(TryRecvError::Disconnected) = abort.try_recv() {
@@ -109,6 +260,65 @@ impl<P: Serialize + DeserializeOwned + Send + core::fmt::Debug + 'static> Remote
         })
     }
 
+    /// Connects over a WebSocket (plain or TLS-secured), framing each bincode packet as a single
+    /// binary message rather than a continuous stream.
+    fn new_websocket(url: &Url) -> anyhow::Result<Self> {
+        let socket = Arc::new(Mutex::new(connect_websocket(url)?));
+
+        let (to_thread, from_main) = crossbeam_channel::unbounded();
+        let (to_main, from_thread) = crossbeam_channel::unbounded();
+
+        let send_socket = Arc::clone(&socket);
+        let send = ControllableThread::new("network send thread", move |abort| -> anyhow::Result<()> {
+            loop {
+                if let Ok(_) | Err(TryRecvError::Disconnected) = abort.try_recv() {
+                    break
+                }
+                while let Ok(packet) = from_main.recv() {
+                    let bytes = bincode::serialize(&packet)?;
+                    send_socket.lock().unwrap().send(Message::Binary(bytes))?;
+                }
+            }
+            Ok(())
+        });
+
+        let recv_socket = Arc::clone(&socket);
+        let recv = ControllableThread::new("network recv thread", move |abort| -> anyhow::Result<()> {
+            loop {
+                if let Ok(_) | Err(TryRecvError::Disconnected) = abort.try_recv() {
+                    break
+                }
+                let message = match recv_socket.lock().unwrap().read() {
+                    Ok(message) => message,
+                    // A read timeout just means no message arrived during our turn with the
+                    // lock; give the send thread a chance and try again.
+                    Err(tungstenite::Error::Io(error))
+                        if error.kind() == std::io::ErrorKind::WouldBlock =>
+                    {
+                        continue
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+                let packet = match message {
+                    Message::Binary(bytes) => bincode::deserialize(&bytes)?,
+                    Message::Close(_) => anyhow::bail!("Relay closed the WebSocket connection"),
+                    _ => continue,
+                };
+                if to_main.send(packet).is_err() {
+                    anyhow::bail!("Couldn't send packet over to the main thread")
+                }
+            }
+            Ok(())
+        });
+
+        Ok(Self {
+            rx: from_thread,
+            tx: to_thread,
+            send,
+            recv,
+        })
+    }
+
     pub fn send(&self, packet: P) -> anyhow::Result<()> {
         if self.tx.send(packet).is_err() {
             anyhow::bail!("Couldn't send packet over to the network thread")
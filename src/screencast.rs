@@ -0,0 +1,192 @@
+//! Live canvas screencasting: publishes the canvas as its own PipeWire video source, so OBS,
+//! browsers, or other spectators can pick it up without mirroring the whole desktop.
+//!
+//! This connects directly to the system PipeWire daemon rather than going through
+//! `org.freedesktop.portal.ScreenCast`: that portal interface exists so a *sandboxed, untrusted*
+//! app can ask the compositor for permission to capture a monitor or window it doesn't own, and
+//! hands back a PipeWire node producing frames the compositor chose. NetCanv is doing the
+//! opposite here — originating a node from content it already owns (its own rendered canvas) — so
+//! there's no screen content to request access to, and no compositor-side source to pick. A plain
+//! `Direction::Output` stream, registered the same way any other PipeWire source (e.g. a virtual
+//! camera) would be, is the correct fit.
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::config::ScreencastConfig;
+
+/// A single captured video frame, ready to be copied into the PipeWire buffer.
+pub struct Frame {
+   pub width: u32,
+   pub height: u32,
+   /// Tightly packed BGRx rows with no per-row padding.
+   pub data: Vec<u8>,
+}
+
+/// Handle to a running screencast session.
+///
+/// Dropping this stops the PipeWire stream and disconnects from the daemon.
+pub struct Screencaster {
+   frames: Sender<Frame>,
+   abort: Sender<()>,
+   thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Screencaster {
+   /// Connects to the system PipeWire daemon and starts streaming a new video source node at the
+   /// given `config.framerate`. `canvas_size` is the initial video format NetCanv offers; frames
+   /// pushed via `push_frame` must match it.
+   pub async fn start(config: &ScreencastConfig, canvas_size: (u32, u32)) -> anyhow::Result<Self> {
+      let (frame_tx, frame_rx) = crossbeam_channel::bounded(1);
+      let (abort_tx, abort_rx) = crossbeam_channel::unbounded();
+      let framerate = config.framerate;
+      let thread = std::thread::Builder::new().name("screencast".into()).spawn(move || {
+         if let Err(error) = pipewire_loop(canvas_size, framerate, frame_rx, abort_rx) {
+            log::error!("screencast thread stopped: {}", error);
+         }
+      })?;
+
+      Ok(Self {
+         frames: frame_tx,
+         abort: abort_tx,
+         thread: Some(thread),
+      })
+   }
+
+   /// Pushes a freshly rendered canvas frame to the PipeWire stream.
+   ///
+   /// Frames are dropped rather than queued if the stream isn't ready to consume one yet, since a
+   /// screencast must never stall NetCanv's own render loop.
+   pub fn push_frame(&self, frame: Frame) {
+      let _ = self.frames.try_send(frame);
+   }
+}
+
+impl Drop for Screencaster {
+   fn drop(&mut self) {
+      let _ = self.abort.send(());
+      if let Some(thread) = self.thread.take() {
+         let _ = thread.join();
+      }
+   }
+}
+
+/// Runs the PipeWire stream on a dedicated thread: connects directly to the system PipeWire
+/// daemon (see the module doc comment for why this doesn't go through the screencast portal),
+/// registers a new `Direction::Output` node negotiating an SPA `video/raw` format (BGRx, canvas
+/// resolution, `framerate`), then on every `process` callback copies the pending frame, if any, into
+/// the PipeWire buffer, respecting its row stride rather than assuming it's tightly packed.
+fn pipewire_loop(
+   canvas_size: (u32, u32),
+   framerate: u32,
+   frames: Receiver<Frame>,
+   abort: Receiver<()>,
+) -> anyhow::Result<()> {
+   pipewire::init();
+
+   let main_loop = pipewire::main_loop::MainLoop::new(None)?;
+   let context = pipewire::context::Context::new(&main_loop)?;
+   let core = context.connect(None)?;
+   let stream = pipewire::stream::Stream::new(
+      &core,
+      "netcanv-screencast",
+      pipewire::properties::properties! {
+         *pipewire::keys::MEDIA_TYPE => "Video",
+         *pipewire::keys::MEDIA_CATEGORY => "Source",
+         *pipewire::keys::MEDIA_ROLE => "Screen",
+      },
+   )?;
+
+   let frame_bytes_per_row = canvas_size.0 as usize * 4;
+   let frame_height = canvas_size.1 as usize;
+   let _listener = stream
+      .add_local_listener()
+      .process(move |stream, _| {
+         let Some(frame) = frames.try_iter().last() else {
+            return;
+         };
+         if let Some(mut buffer) = stream.dequeue_buffer() {
+            let data = &mut buffer.datas_mut()[0];
+            let stride = data.chunk().stride() as usize;
+            if let Some(slice) = data.data() {
+               // The PipeWire buffer's rows may be padded to a stride wider than
+               // `frame_bytes_per_row`, so each row has to be copied individually rather than
+               // with one contiguous memcpy, or the image shears.
+               let row_bytes = frame_bytes_per_row.min(stride).min(frame.data.len());
+               for row in 0..frame_height {
+                  let dst_start = row * stride;
+                  let src_start = row * frame_bytes_per_row;
+                  if dst_start + row_bytes > slice.len() || src_start + row_bytes > frame.data.len() {
+                     break;
+                  }
+                  slice[dst_start..dst_start + row_bytes]
+                     .copy_from_slice(&frame.data[src_start..src_start + row_bytes]);
+               }
+            }
+         }
+      })
+      .register()?;
+
+   stream.connect(
+      pipewire::spa::utils::Direction::Output,
+      None,
+      pipewire::stream::StreamFlags::AUTOCONNECT
+         | pipewire::stream::StreamFlags::MAP_BUFFERS
+         | pipewire::stream::StreamFlags::DRIVER,
+      &mut video_format_params(canvas_size, framerate),
+   )?;
+
+   // `main_loop.run()` only returns once something calls `main_loop.quit()` from within the
+   // loop's own thread, so `abort` (sent from `Screencaster::drop`, on a different thread) can't
+   // be polled directly around it without deadlocking. Instead, attach a pipewire channel to the
+   // loop that quits it, and have a small watcher thread forward the abort signal onto that
+   // channel.
+   let (quit_tx, quit_rx) = pipewire::channel::channel();
+   let weak_loop = main_loop.downgrade();
+   let _quit_receiver = quit_rx.attach(main_loop.loop_(), move |()| {
+      if let Some(main_loop) = weak_loop.upgrade() {
+         main_loop.quit();
+      }
+   });
+   std::thread::Builder::new().name("screencast-watcher".into()).spawn(move || {
+      let _ = abort.recv();
+      let _ = quit_tx.send(());
+   })?;
+
+   main_loop.run();
+
+   Ok(())
+}
+
+/// Builds the single `SPA_PARAM_EnumFormat` POD NetCanv offers: BGRx at `canvas_size` and the
+/// configured framerate.
+fn video_format_params(canvas_size: (u32, u32), framerate: u32) -> Vec<u8> {
+   use pipewire::spa::param::format::{FormatProperties, MediaSubtype, MediaType};
+   use pipewire::spa::param::video::VideoFormat;
+   use pipewire::spa::pod::serialize::PodSerializer;
+   use pipewire::spa::pod::{property, Object, Property, Value};
+   use pipewire::spa::sys::{SPA_PARAM_EnumFormat, SPA_TYPE_OBJECT_Format};
+   use pipewire::spa::utils::{Fraction, Rectangle};
+
+   let (width, height) = canvas_size;
+   let object = Object {
+      type_: SPA_TYPE_OBJECT_Format,
+      id: SPA_PARAM_EnumFormat,
+      properties: vec![
+         property!(FormatProperties::MediaType, Id, MediaType::Video),
+         property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+         property!(FormatProperties::VideoFormat, Id, VideoFormat::BGRx),
+         Property::new(
+            FormatProperties::VideoSize.as_raw(),
+            Value::Rectangle(Rectangle { width, height }),
+         ),
+         Property::new(
+            FormatProperties::VideoFramerate.as_raw(),
+            Value::Fraction(Fraction { num: framerate, denom: 1 }),
+         ),
+      ],
+   };
+
+   let (cursor, _) = PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &Value::Object(object))
+      .expect("failed to serialize the SPA video format POD");
+   cursor.into_inner()
+}
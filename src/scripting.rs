@@ -0,0 +1,265 @@
+//! Embeddable scripting for custom brushes and canvas automation.
+//!
+//! Scripts are small Rhai programs that call into a host API mirroring `Renderer`/`RenderBackend`
+//! (`fill`, `outline`, `line`, `push`/`pop`/`translate`). Calls are recorded as [`Command`]s rather
+//! than applied directly, so a script's drawing replays through [`ScriptingEngine::run`] onto the
+//! same `Renderer`/`RenderBackend` calls a built-in tool makes, and batches like one. The replay
+//! only draws onto the renderer passed to `run`; nothing here sends a script's drawing to peers.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use netcanv_renderer::paws::{Color, LineCap, Point, Rect, Renderer, Vector};
+use netcanv_renderer::RenderBackend;
+use rhai::{Engine, AST};
+
+use crate::config::ScriptPermissions;
+
+/// A recorded host API call, queued while a script runs and replayed onto the real renderer
+/// afterwards.
+enum Command {
+   Push,
+   Pop,
+   Translate(Vector),
+   Scale(Vector),
+   Fill { rect: Rect, color: Color, radius: f32 },
+   Outline { rect: Rect, color: Color, radius: f32, thickness: f32 },
+   Line { a: Point, b: Point, color: Color, cap: LineCap, thickness: f32 },
+   Image { rect: Rect, image_id: i64 },
+}
+
+/// Parses the `cap` string scripts pass to `line`, defaulting to `LineCap::Round` for any
+/// unrecognized value so a typo doesn't fail the whole script.
+fn parse_line_cap(cap: &str) -> LineCap {
+   match cap {
+      "butt" => LineCap::Butt,
+      "square" => LineCap::Square,
+      _ => LineCap::Round,
+   }
+}
+
+/// The bounded instruction/time budget a single script invocation may spend, so a runaway script
+/// can't freeze the render loop.
+#[derive(Clone, Copy)]
+pub struct Budget {
+   pub max_operations: u64,
+   pub max_duration: Duration,
+}
+
+impl Default for Budget {
+   fn default() -> Self {
+      Self {
+         max_operations: 10_000_000,
+         max_duration: Duration::from_millis(100),
+      }
+   }
+}
+
+/// A script loaded from the user scripts directory.
+pub struct Script {
+   pub name: String,
+   pub path: PathBuf,
+   ast: AST,
+}
+
+/// Owns the Rhai engine, the compiled scripts discovered in the scripts directory, and the
+/// command buffer scripts record their drawing into.
+pub struct ScriptingEngine {
+   engine: Engine,
+   commands: Rc<RefCell<Vec<Command>>>,
+   scripts: Vec<Script>,
+   budget: Budget,
+}
+
+impl ScriptingEngine {
+   pub fn new(budget: Budget) -> Self {
+      let mut engine = Engine::new();
+      engine.set_max_operations(budget.max_operations);
+
+      let commands = Rc::new(RefCell::new(Vec::new()));
+      register_host_api(&mut engine, Rc::clone(&commands));
+
+      Self {
+         engine,
+         commands,
+         scripts: Vec::new(),
+         budget,
+      }
+   }
+
+   /// Compiles every `*.rhai` file in `dir`, skipping (and logging) any that fail to parse so one
+   /// broken script doesn't take down the rest.
+   pub fn load_scripts(&mut self, dir: &Path) -> anyhow::Result<()> {
+      self.scripts.clear();
+      fs::create_dir_all(dir)?;
+      for entry in fs::read_dir(dir)? {
+         let entry = entry?;
+         let path = entry.path();
+         if path.extension().and_then(|extension| extension.to_str()) != Some("rhai") {
+            continue;
+         }
+         let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("script").to_owned();
+         let source = fs::read_to_string(&path)?;
+         match self.engine.compile(&source) {
+            Ok(ast) => self.scripts.push(Script { name, path, ast }),
+            Err(error) => log::error!("failed to compile script {:?}: {}", path, error),
+         }
+      }
+      Ok(())
+   }
+
+   pub fn scripts(&self) -> &[Script] {
+      &self.scripts
+   }
+
+   /// Runs `script` against `renderer`, provided `permissions` allows it, under this engine's
+   /// instruction/time budget, then replays every recorded [`Command`] onto `renderer`. `images`
+   /// maps the ids scripts pass to `image()` to the loaded images they refer to; an id with no
+   /// entry is logged and skipped rather than failing the whole script.
+   pub fn run<R>(
+      &mut self,
+      script: &Script,
+      permissions: &ScriptPermissions,
+      renderer: &mut R,
+      images: &HashMap<i64, R::Image>,
+   ) -> anyhow::Result<()>
+   where
+      R: Renderer + RenderBackend,
+   {
+      if !permissions.enabled {
+         anyhow::bail!("script '{}' is not enabled in the user config", script.name);
+      }
+
+      self.commands.borrow_mut().clear();
+      let deadline = Instant::now() + self.budget.max_duration;
+      self.engine.on_progress(move |_operations| {
+         if Instant::now() > deadline {
+            Some("script exceeded its time budget".into())
+         } else {
+            None
+         }
+      });
+      self
+         .engine
+         .eval_ast::<()>(&script.ast)
+         .map_err(|error| anyhow::anyhow!("script '{}' failed: {}", script.name, error))?;
+
+      for command in self.commands.borrow_mut().drain(..) {
+         match command {
+            Command::Push => renderer.push(),
+            Command::Pop => renderer.pop(),
+            Command::Translate(vector) => renderer.translate(vector),
+            Command::Scale(vector) => renderer.scale(vector),
+            Command::Fill { rect, color, radius } => renderer.fill(rect, color, radius),
+            Command::Outline { rect, color, radius, thickness } => {
+               renderer.outline(rect, color, radius, thickness)
+            }
+            Command::Line { a, b, color, cap, thickness } => renderer.line(a, b, color, cap, thickness),
+            Command::Image { rect, image_id } => match images.get(&image_id) {
+               Some(image) => renderer.image(rect, image),
+               None => log::warn!("script '{}' referenced unknown image id {}", script.name, image_id),
+            },
+         }
+      }
+
+      Ok(())
+   }
+}
+
+/// Registers the host API functions scripts call into, each one pushing a [`Command`] onto the
+/// shared buffer instead of drawing immediately.
+fn register_host_api(engine: &mut Engine, commands: Rc<RefCell<Vec<Command>>>) {
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn("push", move || commands.borrow_mut().push(Command::Push));
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn("pop", move || commands.borrow_mut().push(Command::Pop));
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn("translate", move |x: f64, y: f64| {
+         commands.borrow_mut().push(Command::Translate(Vector::new(x as f32, y as f32)));
+      });
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn("scale", move |x: f64, y: f64| {
+         commands.borrow_mut().push(Command::Scale(Vector::new(x as f32, y as f32)));
+      });
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn(
+         "fill",
+         move |x: f64, y: f64, w: f64, h: f64, r: i64, g: i64, b: i64, a: i64, radius: f64| {
+            commands.borrow_mut().push(Command::Fill {
+               rect: Rect::new(Point::new(x as f32, y as f32), Vector::new(w as f32, h as f32)),
+               color: Color::rgba(r as u8, g as u8, b as u8, a as u8),
+               radius: radius as f32,
+            });
+         },
+      );
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn(
+         "outline",
+         move |x: f64,
+               y: f64,
+               w: f64,
+               h: f64,
+               r: i64,
+               g: i64,
+               b: i64,
+               a: i64,
+               radius: f64,
+               thickness: f64| {
+            commands.borrow_mut().push(Command::Outline {
+               rect: Rect::new(Point::new(x as f32, y as f32), Vector::new(w as f32, h as f32)),
+               color: Color::rgba(r as u8, g as u8, b as u8, a as u8),
+               radius: radius as f32,
+               thickness: thickness as f32,
+            });
+         },
+      );
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn(
+         "line",
+         move |ax: f64,
+               ay: f64,
+               bx: f64,
+               by: f64,
+               r: i64,
+               g: i64,
+               b: i64,
+               a: i64,
+               thickness: f64,
+               cap: &str| {
+            commands.borrow_mut().push(Command::Line {
+               a: Point::new(ax as f32, ay as f32),
+               b: Point::new(bx as f32, by as f32),
+               color: Color::rgba(r as u8, g as u8, b as u8, a as u8),
+               cap: parse_line_cap(cap),
+               thickness: thickness as f32,
+            });
+         },
+      );
+   }
+   {
+      let commands = Rc::clone(&commands);
+      engine.register_fn("image", move |x: f64, y: f64, w: f64, h: f64, image_id: i64| {
+         commands.borrow_mut().push(Command::Image {
+            rect: Rect::new(Point::new(x as f32, y as f32), Vector::new(w as f32, h as f32)),
+            image_id,
+         });
+      });
+   }
+}